@@ -1,14 +1,36 @@
-use heck::ShoutySnakeCase;
+use heck::{CamelCase, ShoutySnakeCase};
 use std::io::{Read, Write};
 use std::path::Path;
 use std::process::{Command, Stdio};
 use witx::*;
 
-pub fn generate(wasi: &Path) -> String {
-    let doc = witx::load(&[wasi.join("phases/snapshot/witx/wasi_snapshot_preview1.witx")]).unwrap();
+/// Selects which flavor of bindings [`generate`] emits for each phase.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Config {
+    /// Emit a `Result`-returning wrapper module on top of the raw `extern "C"`
+    /// imports. Wrappers whose parameters are all values or read-only slices
+    /// are safe `fn`s; wrappers that take a raw pointer (e.g. a write buffer)
+    /// are `unsafe fn`s, and `proc_exit` returns `!` rather than a `Result`.
+    pub safe_wrappers: bool,
+    /// Emit real `#[repr]` enums with a `TryFrom` impl alongside the type
+    /// alias and integer constants, which are still emitted and remain the
+    /// types used by the raw imports.
+    pub typed_enums: bool,
+    /// Emit a `#[repr(transparent)]` newtype with bitflags-style operations
+    /// instead of a type alias and a series of `__WASI_*` mask constants.
+    pub typed_flags: bool,
+}
 
-    let mut raw = String::new();
-    raw.push_str(
+/// Generates Rust bindings for each witx `phase` (e.g. the old, ephemeral, and
+/// snapshot ABIs), emitting one module per phase so a single crate can expose
+/// several ABI snapshots side by side. The module name is derived from each
+/// witx file's stem.
+///
+/// The output is run through `rustfmt` when it is available; if it is not, the
+/// unformatted — but still valid — source is returned instead.
+pub fn generate(phases: &[&Path], config: &Config) -> String {
+    let mut src = String::new();
+    src.push_str(
         "\
 // This file is automatically generated, DO NOT EDIT
 //
@@ -18,42 +40,117 @@ pub fn generate(wasi: &Path) -> String {
 
 ",
     );
+
+    for phase in phases {
+        let doc = witx::load(&[phase]).unwrap();
+        src.push_str(&format!("pub mod {} {{\n", phase_module(phase)));
+        render_phase(&doc, config, &mut src);
+        src.push_str("}\n\n");
+    }
+
+    rustfmt(&src).unwrap_or(src)
+}
+
+/// Derives the module name for a phase from its witx file stem, e.g.
+/// `wasi_snapshot_preview1.witx` becomes `wasi_snapshot_preview1`.
+fn phase_module(phase: &Path) -> String {
+    phase
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .expect("witx phase path must have a valid file stem")
+        .to_string()
+}
+
+/// Renders a single phase's datatypes, raw imports, and — when requested — the
+/// safe wrapper module into `src`.
+fn render_phase(doc: &Document, config: &Config, src: &mut String) {
     for ty in doc.datatypes() {
-        ty.render(&mut raw);
-        raw.push_str("\n");
+        // Render the item first: `PointerLengthPair` aliases render to nothing,
+        // and emitting docs ahead of them would leave a floating `///`.
+        let mut body = String::new();
+        match &ty.variant {
+            // The typed enum is emitted *in addition* to the integer alias, not
+            // in place of it: the raw `extern "C"` boundary must stay on the
+            // plain integer repr so that an FFI return of an undefined
+            // discriminant can never materialize an invalid enum value. The
+            // enum + `TryFrom` is a separate surface the consumer converts into.
+            DatatypeVariant::Enum(e) if config.typed_enums => {
+                ty.render(&mut body);
+                body.push_str("\n");
+                e.render_typed(&mut body);
+            }
+            DatatypeVariant::Flags(f) if config.typed_flags => f.render_typed(&mut body),
+            _ => ty.render(&mut body),
+        }
+        if body.is_empty() {
+            continue;
+        }
+        render_docs(&ty.docs, "///", src);
+        src.push_str(&body);
+        src.push_str("\n");
+    }
+    for m in doc.modules() {
+        m.render(src);
+        src.push_str("\n");
+    }
+
+    if !config.safe_wrappers {
+        return;
     }
+
+    // Emit an idiomatic wrapper module on top of the raw `extern "C"` imports
+    // above. Each `__wasi_*` import gets a function that performs the raw call
+    // and translates the WASI `errno` return value into a `Result`. Imports
+    // that take only values and slices get a safe `fn`; imports that take raw
+    // pointers get an `unsafe fn` that keeps those pointers.
+    src.push_str(
+        "\
+pub mod wasi {
+    use super::*;
+
+    /// A WASI `errno` that a raw import returned to signal failure.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Error(pub __wasi_errno_t);
+
+",
+    );
     for m in doc.modules() {
-        m.render(&mut raw);
-        raw.push_str("\n");
+        m.render_safe(src);
+        src.push_str("\n");
     }
+    src.push_str("}\n");
+}
 
-    let mut rustfmt = Command::new("rustfmt")
+/// Formats `input` with `rustfmt`, returning `None` when `rustfmt` is not
+/// installed or fails so the caller can fall back to the unformatted source.
+fn rustfmt(input: &str) -> Option<String> {
+    let mut child = Command::new("rustfmt")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
-        .unwrap();
-    rustfmt
-        .stdin
-        .take()
-        .unwrap()
-        .write_all(raw.as_bytes())
-        .unwrap();
+        .ok()?;
+    // Drop the stdin handle once we've written, so `rustfmt` sees EOF and we
+    // don't deadlock waiting on its output.
+    child.stdin.take()?.write_all(input.as_bytes()).ok()?;
     let mut ret = String::new();
-    rustfmt
-        .stdout
-        .take()
-        .unwrap()
-        .read_to_string(&mut ret)
-        .unwrap();
-    let status = rustfmt.wait().unwrap();
-    assert!(status.success());
-    return ret;
+    child.stdout.take()?.read_to_string(&mut ret).ok()?;
+    if child.wait().ok()?.success() {
+        Some(ret)
+    } else {
+        None
+    }
 }
 
 trait Render {
     fn render(&self, src: &mut String);
 }
 
+/// Renders the safe, `Result`-returning wrapper layer that sits on top of the
+/// raw `extern "C"` imports produced by [`Render`].
+trait RenderSafe {
+    fn render_safe(&self, src: &mut String);
+}
+
 impl Render for Datatype {
     fn render(&self, src: &mut String) {
         match &self.variant {
@@ -89,6 +186,7 @@ impl Render for StructDatatype {
         src.push_str("#[derive(Copy, Clone)]\n");
         src.push_str(&format!("pub struct __wasi_{}_t {{\n", self.name.as_str()));
         for member in self.members.iter() {
+            render_docs(&member.docs, "///", src);
             src.push_str("pub ");
             member.name.render(src);
             src.push_str(": ");
@@ -105,6 +203,7 @@ impl Render for FlagsDatatype {
         self.repr.render(src);
         src.push_str(";\n");
         for (i, variant) in self.flags.iter().enumerate() {
+            render_docs(&variant.docs, "///", src);
             src.push_str(&format!(
                 "pub const __WASI_{}_{}: __wasi_{}_t = 0x{:x};",
                 self.name.as_str().to_shouty_snake_case(),
@@ -122,6 +221,7 @@ impl Render for EnumDatatype {
         self.repr.render(src);
         src.push_str(";\n");
         for (i, variant) in self.variants.iter().enumerate() {
+            render_docs(&variant.docs, "///", src);
             src.push_str(&format!(
                 "pub const __WASI_{}_{}: __wasi_{}_t = {};",
                 self.name.as_str().to_shouty_snake_case(),
@@ -133,6 +233,113 @@ impl Render for EnumDatatype {
     }
 }
 
+impl FlagsDatatype {
+    /// Renders this flag set as a `#[repr(transparent)]` newtype over the
+    /// underlying [`IntRepr`], exposing bitflags-style `|`, `&`, `contains`,
+    /// `empty`, and `all` operations. Because it is `repr(transparent)` it
+    /// remains ABI-compatible with the raw extern signatures, so the safe
+    /// wrapper layer can accept and return it directly.
+    fn render_typed(&self, src: &mut String) {
+        let mut repr = String::new();
+        self.repr.render(&mut repr);
+        let name = self.name.as_str();
+        let all = self
+            .flags
+            .iter()
+            .enumerate()
+            .fold(0u128, |acc, (i, _)| acc | (1 << i));
+
+        src.push_str("#[repr(transparent)]\n");
+        src.push_str("#[derive(Copy, Clone, Debug, PartialEq, Eq)]\n");
+        src.push_str(&format!("pub struct __wasi_{}_t(pub {});\n", name, repr));
+
+        src.push_str(&format!("impl __wasi_{}_t {{\n", name));
+        for (i, variant) in self.flags.iter().enumerate() {
+            render_docs(&variant.docs, "///", src);
+            src.push_str(&format!(
+                "pub const {}: __wasi_{}_t = __wasi_{}_t(0x{:x});\n",
+                variant.name.as_str().to_shouty_snake_case(),
+                name,
+                name,
+                1u128 << i
+            ));
+        }
+        src.push_str(&format!(
+            "pub const fn empty() -> __wasi_{}_t {{ __wasi_{}_t(0) }}\n",
+            name, name
+        ));
+        src.push_str(&format!(
+            "pub const fn all() -> __wasi_{}_t {{ __wasi_{}_t(0x{:x}) }}\n",
+            name, name, all
+        ));
+        src.push_str(&format!(
+            "pub const fn contains(&self, other: __wasi_{}_t) -> bool {{ self.0 & other.0 == other.0 }}\n",
+            name
+        ));
+        src.push_str("}\n");
+
+        src.push_str(&format!(
+            "impl ::std::ops::BitOr for __wasi_{}_t {{\n\
+             type Output = __wasi_{}_t;\n\
+             fn bitor(self, other: __wasi_{}_t) -> __wasi_{}_t {{ __wasi_{}_t(self.0 | other.0) }}\n\
+             }}\n",
+            name, name, name, name, name
+        ));
+        src.push_str(&format!(
+            "impl ::std::ops::BitAnd for __wasi_{}_t {{\n\
+             type Output = __wasi_{}_t;\n\
+             fn bitand(self, other: __wasi_{}_t) -> __wasi_{}_t {{ __wasi_{}_t(self.0 & other.0) }}\n\
+             }}",
+            name, name, name, name, name
+        ));
+    }
+}
+
+impl EnumDatatype {
+    /// Renders this enum as a real `#[repr]` Rust enum plus a `TryFrom` impl.
+    /// This is a *separate* typed surface — distinct from the raw
+    /// `__wasi_<name>_t` integer alias used at the `extern "C"` boundary — that
+    /// the consumer converts into with `TryFrom`, so an FFI value outside the
+    /// defined discriminants is rejected rather than producing an invalid enum.
+    /// The integer repr is the one chosen by [`IntRepr::render`], and `as` casts
+    /// back to that repr recover the discriminant.
+    fn render_typed(&self, src: &mut String) {
+        let mut repr = String::new();
+        self.repr.render(&mut repr);
+        let name = self.name.as_str().to_camel_case();
+
+        src.push_str(&format!("#[repr({})]\n", repr));
+        src.push_str("#[derive(Copy, Clone, Debug, PartialEq, Eq)]\n");
+        src.push_str(&format!("pub enum {} {{\n", name));
+        for (i, variant) in self.variants.iter().enumerate() {
+            render_docs(&variant.docs, "///", src);
+            src.push_str(&format!("{} = {},\n", variant_ident(&variant.name), i));
+        }
+        src.push_str("}\n");
+
+        src.push_str(&format!(
+            "impl ::std::convert::TryFrom<{}> for {} {{\n",
+            repr, name
+        ));
+        src.push_str("type Error = ();\n");
+        src.push_str(&format!(
+            "fn try_from(value: {}) -> ::std::result::Result<Self, Self::Error> {{\n",
+            repr
+        ));
+        src.push_str("match value {\n");
+        for (i, variant) in self.variants.iter().enumerate() {
+            src.push_str(&format!(
+                "{} => Ok({}::{}),\n",
+                i,
+                name,
+                variant_ident(&variant.name)
+            ));
+        }
+        src.push_str("_ => Err(()),\n");
+        src.push_str("}\n}\n}");
+    }
+}
+
 impl Render for IntRepr {
     fn render(&self, src: &mut String) {
         match self {
@@ -222,8 +429,221 @@ impl Render for Module {
     }
 }
 
+impl RenderSafe for Module {
+    fn render_safe(&self, src: &mut String) {
+        for f in self.funcs() {
+            f.render_safe(src);
+            src.push_str("\n");
+        }
+    }
+}
+
+impl RenderSafe for InterfaceFunc {
+    fn render_safe(&self, src: &mut String) {
+        // The first result, when present, is the `errno` the import returns to
+        // signal success or failure. Any remaining results are out-parameters
+        // that the raw import writes through `*mut` pointers; the wrapper
+        // allocates them on the stack and returns them by value.
+        let outs = self.results.iter().skip(1).collect::<Vec<_>>();
+
+        // A parameter passed as a bare pointer (a `*mut u8` write buffer whose
+        // length lives in a *separate* `$size` param, as in `random_get` /
+        // `fd_prestat_dir_name` / `fd_readdir`, or an indirection-heavy type
+        // like `args_get`'s `*mut *mut u8`) cannot be turned into a sound safe
+        // reference here — the length/shape isn't known at this param. Rather
+        // than fabricate a misleading `&mut T`, such a function is emitted as an
+        // `unsafe fn` that keeps the raw pointer, leaving the safety contract
+        // with the caller.
+        let raw_pointers = self
+            .params
+            .iter()
+            .any(|p| {
+                matches!(p.type_.passed_by(), DatatypePassedBy::Pointer)
+                    || matches!(
+                        resolve(&p.type_),
+                        DatatypeIdent::Pointer(_) | DatatypeIdent::ConstPointer(_)
+                    )
+            });
+
+        render_docs(&self.docs, "///", src);
+        if raw_pointers {
+            // Document the contract these raw-pointer wrappers place on the
+            // caller (and satisfy `clippy::missing_safety_doc`).
+            src.push_str("///\n");
+            src.push_str("/// # Safety\n");
+            src.push_str("///\n");
+            src.push_str(
+                "/// Takes raw pointers whose length, mutability, and validity the witx\n\
+                 /// signature does not capture at this parameter; the caller must ensure each\n\
+                 /// points to a valid, correctly sized allocation for the duration of the call.\n",
+            );
+        }
+        src.push_str(if raw_pointers { "pub unsafe fn " } else { "pub fn " });
+        src.push_str(self.name.as_str());
+        src.push_str("(");
+        for param in self.params.iter() {
+            render_docs(&param.docs, "//", src);
+            param.render_safe(src);
+            src.push_str(",");
+        }
+        src.push_str(")");
+
+        // `proc_exit` never returns, so it gets neither a `Result` nor any
+        // out-parameter handling.
+        if self.name.as_str() == "proc_exit" {
+            src.push_str(" -> ! {\n");
+            src.push_str("unsafe { __wasi_proc_exit(");
+            self.render_safe_args(src, &outs);
+            src.push_str(") }\n}");
+            return;
+        }
+
+        src.push_str(" -> Result<");
+        match outs.as_slice() {
+            [] => src.push_str("()"),
+            [one] => one.type_.render(src),
+            many => {
+                src.push_str("(");
+                for result in many {
+                    result.type_.render(src);
+                    src.push_str(", ");
+                }
+                src.push_str(")");
+            }
+        }
+        src.push_str(", Error> {\n");
+        // In a safe `fn`, confine the raw call and the `assume_init` of the
+        // out-parameters to an `unsafe` block; an `unsafe fn` already provides
+        // that context, so adding a block there would be redundant.
+        if !raw_pointers {
+            src.push_str("unsafe {\n");
+        }
+
+        for result in outs.iter() {
+            src.push_str("let mut ");
+            result.name.render(src);
+            src.push_str(" = ::std::mem::MaybeUninit::uninit();\n");
+        }
+
+        let has_errno = self.results.get(0).is_some();
+        if has_errno {
+            src.push_str("let ret = ");
+        }
+        src.push_str("__wasi_");
+        src.push_str(self.name.as_str());
+        src.push_str("(");
+        self.render_safe_args(src, &outs);
+        src.push_str(");\n");
+        if has_errno {
+            // The extern boundary stays on the integer errno alias, so a plain
+            // zero comparison is all that's needed to detect success.
+            src.push_str("if ret != 0 {\nreturn Err(Error(ret));\n}\n");
+        }
+
+        src.push_str("Ok(");
+        match outs.as_slice() {
+            [] => src.push_str("()"),
+            [one] => {
+                one.name.render(src);
+                src.push_str(".assume_init()");
+            }
+            many => {
+                src.push_str("(");
+                for result in many {
+                    result.name.render(src);
+                    src.push_str(".assume_init(), ");
+                }
+                src.push_str(")");
+            }
+        }
+        if raw_pointers {
+            src.push_str(")\n}");
+        } else {
+            src.push_str(")\n}\n}");
+        }
+    }
+}
+
+impl InterfaceFunc {
+    /// Renders the argument list used to call the raw import from inside the
+    /// safe wrapper: the wrapper's own parameters followed by the addresses of
+    /// the stack-allocated out-parameters.
+    fn render_safe_args(&self, src: &mut String, outs: &[&InterfaceFuncParam]) {
+        for param in self.params.iter() {
+            param.render_safe_arg(src);
+            src.push_str(",");
+        }
+        for result in outs {
+            result.name.render(src);
+            src.push_str(".as_mut_ptr(),");
+        }
+    }
+}
+
+impl InterfaceFuncParam {
+    /// Renders this parameter as it appears in the safe wrapper's signature.
+    fn render_safe(&self, src: &mut String) {
+        match self.type_.passed_by() {
+            DatatypePassedBy::Value(_) => {
+                self.name.render(src);
+                src.push_str(": ");
+                self.type_.render(src);
+            }
+            // A bare pointer param carries no length or const/mut information
+            // at this layer (see `raw_pointers` in `render_safe`), so it is kept
+            // as a raw `*mut` — matching the extern signature — and the
+            // enclosing function is an `unsafe fn`.
+            DatatypePassedBy::Pointer => {
+                self.name.render(src);
+                src.push_str(": *mut ");
+                self.type_.render(src);
+            }
+            // The raw import splits arrays and strings into a
+            // `*const T`/`usize` pointer-length pair; the wrapper collapses
+            // that back into a single borrowed slice (or `&str`) and derives
+            // both halves at the call site. These pairs are always read-only in
+            // the witx definitions, so they map to `&[T]`/`&str`; writable
+            // buffers are instead expressed as a raw pointer plus a separate
+            // length param, keep their raw pointer type, and make the
+            // enclosing function an `unsafe fn`.
+            DatatypePassedBy::PointerLengthPair => {
+                self.name.render(src);
+                src.push_str(": ");
+                match resolve(&self.type_) {
+                    DatatypeIdent::Array(x) => {
+                        src.push_str("&[");
+                        x.render(src);
+                        src.push_str("]");
+                    }
+                    DatatypeIdent::Builtin(BuiltinType::String) => src.push_str("&str"),
+                    x => panic!("unexpected pointer length pair type {:?}", x),
+                }
+            }
+        }
+    }
+
+    /// Renders this parameter as an argument when calling the raw import.
+    fn render_safe_arg(&self, src: &mut String) {
+        match self.type_.passed_by() {
+            DatatypePassedBy::Value(_) => self.name.render(src),
+            // The raw pointer is already the right type; pass it straight
+            // through.
+            DatatypePassedBy::Pointer => self.name.render(src),
+            // Split the slice back into the pointer and length the raw import
+            // expects. `str::as_ptr` already yields a `*const u8`.
+            DatatypePassedBy::PointerLengthPair => {
+                self.name.render(src);
+                src.push_str(".as_ptr(), ");
+                self.name.render(src);
+                src.push_str(".len()");
+            }
+        }
+    }
+}
+
 impl Render for InterfaceFunc {
     fn render(&self, src: &mut String) {
+        render_docs(&self.docs, "///", src);
         src.push_str("#[link_name = \"");
         src.push_str(self.name.as_str());
         src.push_str("\"]\n");
@@ -231,6 +651,7 @@ impl Render for InterfaceFunc {
         src.push_str(self.name.as_str());
         src.push_str("(");
         for param in self.params.iter() {
+            render_docs(&param.docs, "//", src);
             param.render(src);
             src.push_str(",");
         }
@@ -311,6 +732,32 @@ impl Render for Id {
     }
 }
 
+/// Emits the witx documentation `docs` as a run of comment lines, each led by
+/// `prefix` (`///` for items, `//` for function parameters, where doc comments
+/// are not allowed). Empty when the source has no documentation.
+fn render_docs(docs: &str, prefix: &str, src: &mut String) {
+    for line in docs.lines() {
+        src.push_str(prefix);
+        if !line.is_empty() {
+            src.push_str(" ");
+            src.push_str(line);
+        }
+        src.push_str("\n");
+    }
+}
+
+/// Produces a valid, CamelCase Rust enum-variant identifier from a witx
+/// variant name. Some names (e.g. the errno `2big`) begin with a digit, which
+/// is not a legal identifier start, so those are prefixed with `E`.
+fn variant_ident(name: &Id) -> String {
+    let camel = name.as_str().to_camel_case();
+    if camel.chars().next().map_or(false, |c| c.is_ascii_digit()) {
+        format!("E{}", camel)
+    } else {
+        camel
+    }
+}
+
 fn resolve(ty: &DatatypeIdent) -> &DatatypeIdent {
     if let DatatypeIdent::Ident(i) = ty {
         if let DatatypeVariant::Alias(a) = &i.variant {